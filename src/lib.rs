@@ -1,24 +1,64 @@
-use spidev::Spidev;
-use spidev::SpidevOptions;
-use spidev::SpidevTransfer;
-use spidev::SPI_MODE_0;
+use embedded_hal::spi::SpiDevice;
 use std::fmt::Debug;
 use std::fmt::Formatter;
-use std::io::Write;
 use std::sync::Arc;
 use std::sync::Mutex;
 
-type Result<T = ()> = std::result::Result<T, Box<std::error::Error>>;
+#[cfg(feature = "spidev")]
+pub use self::spidev_device::{SpiConfig, SpidevDevice};
+
+type Result<T = ()> = std::result::Result<T, Box<dyn std::error::Error>>;
 
 // Mcp23s17 register addresses -- Source:
 // https://github.com/piface/pifacecommon/blob/006bca14c18d43ba2d9eafaa84ef83b512c51cf6/pifacecommon/mcp23s17.py#L17
 const IODIRA: u8 = 0x0; // I/O direction A
 const IODIRB: u8 = 0x1; // I/O direction B
+const IPOLA: u8 = 0x2; // input polarity A
+const IPOLB: u8 = 0x3; // input polarity B
+const GPINTENA: u8 = 0x4; // interrupt-on-change enable A
+const GPINTENB: u8 = 0x5; // interrupt-on-change enable B
+const DEFVALA: u8 = 0x6; // interrupt default value A
+const DEFVALB: u8 = 0x7; // interrupt default value B
+const INTCONA: u8 = 0x8; // interrupt control A
+const INTCONB: u8 = 0x9; // interrupt control B
+const IOCON: u8 = 0xA; // configuration
+const GPPUA: u8 = 0xC; // port A pullups
 const GPPUB: u8 = 0xD; // port B pullups
+const INTFA: u8 = 0xE; // interrupt flag A
+const INTFB: u8 = 0xF; // interrupt flag B
+const INTCAPA: u8 = 0x10; // interrupt capture A
+const INTCAPB: u8 = 0x11; // interrupt capture B
 const GPIOA: u8 = 0x12; // port A
 const GPIOB: u8 = 0x13; // port B
+const OLATA: u8 = 0x14; // output latch A
+const OLATB: u8 = 0x15; // output latch B
+
+// IOCON.HAEN -- enables the hardware address pins (A2/A1/A0), so several chips
+// can share one chip-select line, each addressed independently.
+const IOCON_HAEN: u8 = 0x08;
+
+// IOCON.MIRROR -- OR the two interrupt pins together so either port's event
+// drives both INTA and INTB.
+const IOCON_MIRROR: u8 = 0x40;
 
-const HARDWARE_ADDRESS: u8 = 0;
+/// Configures the chip a given [`Expander`] talks to.
+///
+/// `hardware_address` is the 3-bit A2/A1/A0 value baked into every opcode
+/// byte; it defaults to 0, reproducing the historical behaviour. Bus
+/// parameters (clock, SPI mode) belong to the `SpiDevice` the caller supplies
+/// -- for the `spidev` backend they live in [`SpiConfig`].
+#[derive(Clone, Copy)]
+pub struct Config {
+    pub hardware_address: u8,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            hardware_address: 0,
+        }
+    }
+}
 
 #[derive(Clone, Copy)]
 pub enum IoValue {
@@ -26,21 +66,105 @@ pub enum IoValue {
     High,
 }
 
+/// Whether a pin is driven by the expander or read from the outside world.
 #[derive(Clone, Copy)]
-enum PortLabel {
-    Out,
-    In,
+pub enum Direction {
+    Output,
+    Input,
 }
 
-impl PortLabel {
-    fn address(&self) -> u8 {
+// The two 8-bit banks. Pins 0..=7 live on port A, pins 8..=15 on port B; the
+// low three bits of the pin number index the bit within the bank.
+#[derive(Clone, Copy)]
+enum Port {
+    A,
+    B,
+}
+
+impl Port {
+    fn of_pin(pin: u8) -> Port {
+        if pin < 8 {
+            Port::A
+        } else {
+            Port::B
+        }
+    }
+
+    fn gpio(self) -> u8 {
+        match self {
+            Port::A => GPIOA,
+            Port::B => GPIOB,
+        }
+    }
+
+    fn iodir(self) -> u8 {
         match self {
-            PortLabel::Out => GPIOA,
-            PortLabel::In => GPIOB,
+            Port::A => IODIRA,
+            Port::B => IODIRB,
+        }
+    }
+
+    fn ipol(self) -> u8 {
+        match self {
+            Port::A => IPOLA,
+            Port::B => IPOLB,
+        }
+    }
+
+    fn gppu(self) -> u8 {
+        match self {
+            Port::A => GPPUA,
+            Port::B => GPPUB,
+        }
+    }
+
+    fn gpinten(self) -> u8 {
+        match self {
+            Port::A => GPINTENA,
+            Port::B => GPINTENB,
+        }
+    }
+
+    fn defval(self) -> u8 {
+        match self {
+            Port::A => DEFVALA,
+            Port::B => DEFVALB,
+        }
+    }
+
+    fn intcon(self) -> u8 {
+        match self {
+            Port::A => INTCONA,
+            Port::B => INTCONB,
+        }
+    }
+
+    fn intf(self) -> u8 {
+        match self {
+            Port::A => INTFA,
+            Port::B => INTFB,
+        }
+    }
+
+    fn intcap(self) -> u8 {
+        match self {
+            Port::A => INTCAPA,
+            Port::B => INTCAPB,
         }
     }
 }
 
+/// Selects what arms an interrupt on a pin enabled via
+/// [`Expander::enable_interrupt`].
+#[derive(Clone, Copy)]
+pub enum InterruptMode {
+    /// Fire whenever the pin changes from its previous value (INTCON bit 0).
+    OnChange,
+    /// Fire whenever the pin differs from `value`, the default compare value
+    /// stored in DEFVAL (INTCON bit 1).
+    Compare(IoValue),
+}
+
 pub trait Reader {
     fn read_value(&self) -> Result<IoValue>;
 }
@@ -51,84 +175,265 @@ pub trait Writer: Reader {
     fn set_value(&self, value: IoValue) -> Result;
 }
 
-pub type Input = Box<Reader + Send>;
-pub type Output = Box<Writer + Send>;
+pub type Input = Box<dyn Reader + Send>;
+pub type Output = Box<dyn Writer + Send>;
 
-#[derive(Clone)]
-pub struct Expander {
-    spi: Arc<Mutex<Spidev>>,
+// The mutable state guarded by a single lock: the bus plus a cached copy of
+// the output latches (OLATA/OLATB). Keeping them under one lock lets a
+// single-pin write mask the cached byte and emit just one transfer, and keeps
+// concurrent writers of the same port from racing.
+struct State<SPI> {
+    spi: SPI,
+    latch: [u8; 2],
 }
 
-impl Expander {
-    pub fn new(device: &str) -> Result<Self> {
-        let mut spi = Spidev::open(device)?;
-        spi.configure(
-            SpidevOptions::new()
-                .bits_per_word(8)
-                .max_speed_hz(100_000)
-                .mode(SPI_MODE_0),
-        )?;
-        write_byte(&mut spi, GPIOA, 0)?;
-        write_byte(&mut spi, IODIRA, 0)?; // GPIOA are outputs
-        write_byte(&mut spi, IODIRB, 0xFF)?; // GPIOB are input
-        write_byte(&mut spi, GPPUB, 0xFF)?; // Enable input pullups
+// Shared between an `Expander` and every `Pin` it hands out: the guarded state
+// plus the 3-bit hardware address that selects this chip on the chip-select
+// line.
+struct Inner<SPI> {
+    state: Mutex<State<SPI>>,
+    address: u8,
+}
+
+pub struct Expander<SPI> {
+    inner: Arc<Inner<SPI>>,
+}
+
+// Derived manually: `#[derive(Clone)]` would demand `SPI: Clone`, but the bus
+// lives behind the `Arc<Mutex<_>>`, so cloning an `Expander` only clones the
+// shared handle.
+impl<SPI> Clone for Expander<SPI> {
+    fn clone(&self) -> Self {
+        Expander {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<SPI> Expander<SPI>
+where
+    SPI: SpiDevice + Send + 'static,
+    SPI::Error: std::error::Error + Send + Sync + 'static,
+{
+    pub fn new(device: SPI) -> Result<Self> {
+        Expander::with_config(device, Config::default())
+    }
+
+    pub fn with_config(mut device: SPI, config: Config) -> Result<Self> {
+        let address = config.hardware_address;
+        // Enable address decoding first (while HAEN is still off the chip
+        // answers to any address), so subsequent transfers reach this chip
+        // alone even when it shares a chip-select with its siblings.
+        write_byte(&mut device, address, IOCON, IOCON_HAEN)?;
+        write_byte(&mut device, address, GPIOA, 0)?;
+        write_byte(&mut device, address, IODIRA, 0)?; // GPIOA are outputs
+        write_byte(&mut device, address, IODIRB, 0xFF)?; // GPIOB are input
+        write_byte(&mut device, address, GPPUB, 0xFF)?; // Enable input pullups
+        // Seed the cache from the chip's own output latches so the first
+        // single-pin write preserves whatever the other bits already hold.
+        let latch = [
+            read_byte(&mut device, address, OLATA)?,
+            read_byte(&mut device, address, OLATB)?,
+        ];
         Ok(Expander {
-            spi: Arc::new(Mutex::new(spi)),
+            inner: Arc::new(Inner {
+                state: Mutex::new(State { spi: device, latch }),
+                address,
+            }),
         })
     }
 
+    /// Returns a handle to drive pin `pin_num` (0..=15, where 0..=7 are port A
+    /// and 8..=15 port B). The pin is addressed by number alone; its direction
+    /// is whatever [`set_direction`] last made it, defaulting to output for
+    /// port A and input for port B. Call [`set_direction`] with
+    /// [`Direction::Output`] before driving a port-B pin.
+    ///
+    /// [`set_direction`]: Expander::set_direction
     pub fn output(&self, pin_num: u8) -> Output {
         Box::new(Pin {
-            spi: self.spi.clone(),
-            label: PortLabel::Out,
+            inner: self.inner.clone(),
             num: pin_num,
         })
     }
 
+    /// Returns a handle to read pin `pin_num` (0..=15, where 0..=7 are port A
+    /// and 8..=15 port B). The pin is addressed by number alone; its direction
+    /// is whatever [`set_direction`] last made it, defaulting to output for
+    /// port A and input for port B. Call [`set_direction`] with
+    /// [`Direction::Input`] before reading a port-A pin.
+    ///
+    /// [`set_direction`]: Expander::set_direction
     pub fn input(&self, pin_num: u8) -> Input {
         Box::new(Pin {
-            spi: self.spi.clone(),
-            label: PortLabel::In,
+            inner: self.inner.clone(),
             num: pin_num,
         })
     }
 
+    /// Sets the direction of a single pin (IODIRA/IODIRB), leaving the other
+    /// fifteen untouched.
+    pub fn set_direction(&self, pin: u8, direction: Direction) -> Result {
+        // A set IODIR bit means "input"; a cleared bit means "output".
+        let bit = match direction {
+            Direction::Output => false,
+            Direction::Input => true,
+        };
+        let mut state = self.inner.state.lock().unwrap();
+        update_bit(&mut state.spi, self.inner.address, Port::of_pin(pin).iodir(), pin & 7, bit)
+    }
+
+    /// Enables or disables the 100 kΩ input pull-up on a single pin
+    /// (GPPUA/GPPUB).
+    pub fn set_pull_up(&self, pin: u8, enabled: bool) -> Result {
+        let mut state = self.inner.state.lock().unwrap();
+        update_bit(&mut state.spi, self.inner.address, Port::of_pin(pin).gppu(), pin & 7, enabled)
+    }
+
+    /// Inverts (or restores) the read polarity of a single input pin
+    /// (IPOLA/IPOLB): while set, the GPIO register reports the opposite of the
+    /// physical level.
+    pub fn set_input_polarity(&self, pin: u8, inverted: bool) -> Result {
+        let mut state = self.inner.state.lock().unwrap();
+        update_bit(&mut state.spi, self.inner.address, Port::of_pin(pin).ipol(), pin & 7, inverted)
+    }
+
+    /// Arms the interrupt-on-change unit for a single pin: sets its INTCON and
+    /// DEFVAL bits per `mode` and then enables it in GPINTEN. Once armed, a
+    /// qualifying edge pulls the chip's INT line; call [`captured_values`] to
+    /// read what changed and clear the condition.
+    ///
+    /// [`captured_values`]: Expander::captured_values
+    pub fn enable_interrupt(&self, pin: u8, mode: InterruptMode) -> Result {
+        let port = Port::of_pin(pin);
+        let bit = pin & 7;
+        let mut state = self.inner.state.lock().unwrap();
+        let addr = self.inner.address;
+        match mode {
+            InterruptMode::OnChange => {
+                update_bit(&mut state.spi, addr, port.intcon(), bit, false)?;
+            }
+            InterruptMode::Compare(value) => {
+                let high = match value {
+                    IoValue::Low => false,
+                    IoValue::High => true,
+                };
+                update_bit(&mut state.spi, addr, port.defval(), bit, high)?;
+                update_bit(&mut state.spi, addr, port.intcon(), bit, true)?;
+            }
+        }
+        update_bit(&mut state.spi, addr, port.gpinten(), bit, true)
+    }
+
+    /// Disarms the interrupt-on-change unit for a single pin (clears its
+    /// GPINTEN bit).
+    pub fn disable_interrupt(&self, pin: u8) -> Result {
+        let port = Port::of_pin(pin);
+        let mut state = self.inner.state.lock().unwrap();
+        update_bit(&mut state.spi, self.inner.address, port.gpinten(), pin & 7, false)
+    }
+
+    /// Sets IOCON.MIRROR, OR-ing the INTA and INTB pins so either port's
+    /// interrupt drives both lines.
+    pub fn set_interrupt_mirror(&self, enabled: bool) -> Result {
+        let mut state = self.inner.state.lock().unwrap();
+        let addr = self.inner.address;
+        let current = read_byte(&mut state.spi, addr, IOCON)?;
+        let next = if enabled {
+            current | IOCON_MIRROR
+        } else {
+            current & !IOCON_MIRROR
+        };
+        if current != next {
+            write_byte(&mut state.spi, addr, IOCON, next)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the INTF registers, returning a bitmask of the pins (0..=15)
+    /// currently flagging an interrupt.
+    pub fn flagged_pins(&self) -> Result<u16> {
+        let mut state = self.inner.state.lock().unwrap();
+        read_pair(&mut state.spi, self.inner.address, Port::A.intf(), Port::B.intf())
+    }
+
+    /// Reads the INTCAP registers, returning the port state latched at the
+    /// moment of the interrupt as a bitmask over pins 0..=15. Reading INTCAP
+    /// clears the interrupt condition on the chip.
+    pub fn captured_values(&self) -> Result<u16> {
+        let mut state = self.inner.state.lock().unwrap();
+        read_pair(&mut state.spi, self.inner.address, Port::A.intcap(), Port::B.intcap())
+    }
+
+    /// Atomically sets the output pins selected by `mask` to the corresponding
+    /// bits of `value`, leaving every other pin untouched. Pins 0..=7 live on
+    /// port A and 8..=15 on port B; each port whose byte actually changes is
+    /// flushed in a single transfer.
+    pub fn set_output_byte(&self, mask: u16, value: u16) -> Result {
+        let mut state = self.inner.state.lock().unwrap();
+        write_latch(&mut state, self.inner.address, mask, value)
+    }
+
+    /// Overwrites all sixteen output-latch bits at once from `value`. Each port
+    /// whose byte differs from the cached latch is flushed in a single
+    /// transfer; a port whose byte is unchanged is not written.
+    pub fn write_port_atomic(&self, value: u16) -> Result {
+        let mut state = self.inner.state.lock().unwrap();
+        write_latch(&mut state, self.inner.address, 0xFFFF, value)
+    }
+
     pub fn output_byte(&self) -> Result<u8> {
-        let spi = &self.spi.lock().unwrap();
-        Ok(read_port(spi, PortLabel::Out)?)
+        let mut state = self.inner.state.lock().unwrap();
+        read_port(&mut state.spi, self.inner.address, Port::A)
     }
 
     pub fn input_byte(&self) -> Result<u8> {
-        let spi = &self.spi.lock().unwrap();
-        Ok(read_port(spi, PortLabel::In)?)
+        let mut state = self.inner.state.lock().unwrap();
+        read_port(&mut state.spi, self.inner.address, Port::B)
     }
 }
 
-impl Debug for Expander {
+impl<SPI> Debug for Expander<SPI>
+where
+    SPI: SpiDevice,
+    SPI::Error: std::error::Error + Send + Sync + 'static,
+{
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
-        let spi = &self.spi.lock().unwrap();
-        let in_byte = read_port(spi, PortLabel::In)
+        let mut state = self.inner.state.lock().unwrap();
+        let addr = self.inner.address;
+        let a_byte = read_port(&mut state.spi, addr, Port::A)
             .map(|b| b.to_string())
             .unwrap_or("NONE".to_string());
-        let out_byte = read_port(spi, PortLabel::Out)
+        let b_byte = read_port(&mut state.spi, addr, Port::B)
             .map(|b| b.to_string())
             .unwrap_or("NONE".to_string());
-        write!(f, "{{ In: {}, Out: {} }}", in_byte, out_byte)
+        write!(f, "{{ A: {}, B: {} }}", a_byte, b_byte)
     }
 }
 
-#[derive(Clone)]
-pub struct Pin {
-    spi: Arc<Mutex<Spidev>>,
-    label: PortLabel,
+pub struct Pin<SPI> {
+    inner: Arc<Inner<SPI>>,
     num: u8,
 }
 
-impl Reader for Pin {
+impl<SPI> Clone for Pin<SPI> {
+    fn clone(&self) -> Self {
+        Pin {
+            inner: self.inner.clone(),
+            num: self.num,
+        }
+    }
+}
+
+impl<SPI> Reader for Pin<SPI>
+where
+    SPI: SpiDevice,
+    SPI::Error: std::error::Error + Send + Sync + 'static,
+{
     fn read_value(&self) -> Result<IoValue> {
-        let mask = 1 << self.num;
-        let spi = self.spi.lock().unwrap();
-        let read = read_port(&spi, self.label)?;
+        let mask = 1 << (self.num & 7);
+        let mut state = self.inner.state.lock().unwrap();
+        let read = read_port(&mut state.spi, self.inner.address, Port::of_pin(self.num))?;
         Ok(match read & mask {
             0_u8 => IoValue::Low,
             _ => IoValue::High,
@@ -136,7 +441,11 @@ impl Reader for Pin {
     }
 }
 
-impl Writer for Pin {
+impl<SPI> Writer for Pin<SPI>
+where
+    SPI: SpiDevice,
+    SPI::Error: std::error::Error + Send + Sync + 'static,
+{
     fn set_low(&self) -> Result {
         self.set_value(IoValue::Low)
     }
@@ -146,51 +455,208 @@ impl Writer for Pin {
     }
 
     fn set_value(&self, value: IoValue) -> Result {
-        let mut spi = self.spi.lock().unwrap();
-        let did_read = read_port(&spi, self.label)?;
-
-        // calculate the state to write (to_write)
-        let mask = 1 << self.num;
-        let to_write = match value {
-            IoValue::Low => did_read & !mask,
-            IoValue::High => did_read | mask,
+        // Mask the cached latch rather than reading the port back first: one
+        // transfer instead of two, and no read-modify-write race with a
+        // sibling `Pin` on the same port.
+        let mask = 1u16 << self.num;
+        let bits = match value {
+            IoValue::Low => 0,
+            IoValue::High => mask,
         };
+        let mut state = self.inner.state.lock().unwrap();
+        write_latch(&mut state, self.inner.address, mask, bits)
+    }
+}
 
-        // write
-        if did_read != to_write {
-            write_port(&mut spi, self.label, to_write)?;
-        }
-
-        Ok(())
+// Apply a masked update to the cached output latch, then flush only the
+// port(s) whose byte actually changed -- one `write_byte` per touched port.
+fn write_latch<SPI>(state: &mut State<SPI>, hw_addr: u8, mask: u16, value: u16) -> Result
+where
+    SPI: SpiDevice,
+    SPI::Error: std::error::Error + Send + Sync + 'static,
+{
+    let current = u16::from(state.latch[0]) | (u16::from(state.latch[1]) << 8);
+    let next = (current & !mask) | (value & mask);
+    let next_a = next as u8;
+    let next_b = (next >> 8) as u8;
+    if next_a != state.latch[0] {
+        write_byte(&mut state.spi, hw_addr, GPIOA, next_a)?;
+        state.latch[0] = next_a;
+    }
+    if next_b != state.latch[1] {
+        write_byte(&mut state.spi, hw_addr, GPIOB, next_b)?;
+        state.latch[1] = next_b;
     }
+    Ok(())
 }
 
-fn read_port(spi: &Spidev, label: PortLabel) -> Result<u8> {
-    read_byte(spi, label.address())
+fn read_port<SPI>(spi: &mut SPI, hw_addr: u8, port: Port) -> Result<u8>
+where
+    SPI: SpiDevice,
+    SPI::Error: std::error::Error + Send + Sync + 'static,
+{
+    read_byte(spi, hw_addr, port.gpio())
 }
 
-fn read_byte(spi: &Spidev, address: u8) -> Result<u8> {
-    let tx_buf = [read_cmd(), address, 0];
-    let mut rx_buf = [0u8; 3];
-    let mut transfer = SpidevTransfer::read_write(&tx_buf, &mut rx_buf);
-    spi.transfer(&mut transfer)?;
-    Ok(rx_buf[2])
+// Read a port-A/port-B register pair into a single 16-bit mask, port A in the
+// low byte and port B in the high byte (matching the pin numbering).
+fn read_pair<SPI>(spi: &mut SPI, hw_addr: u8, low: u8, high: u8) -> Result<u16>
+where
+    SPI: SpiDevice,
+    SPI::Error: std::error::Error + Send + Sync + 'static,
+{
+    let low_byte = read_byte(spi, hw_addr, low)?;
+    let high_byte = read_byte(spi, hw_addr, high)?;
+    Ok(u16::from(low_byte) | (u16::from(high_byte) << 8))
 }
 
-fn write_port(spi: &mut Spidev, label: PortLabel, byte: u8) -> Result {
-    write_byte(spi, label.address(), byte)
+// Read-modify-write a single bit of a register, preserving the others.
+fn update_bit<SPI>(spi: &mut SPI, hw_addr: u8, address: u8, bit: u8, value: bool) -> Result
+where
+    SPI: SpiDevice,
+    SPI::Error: std::error::Error + Send + Sync + 'static,
+{
+    let mask = 1 << bit;
+    let current = read_byte(spi, hw_addr, address)?;
+    let next = if value { current | mask } else { current & !mask };
+    if current != next {
+        write_byte(spi, hw_addr, address, next)?;
+    }
+    Ok(())
 }
 
-fn write_byte(spi: &mut Spidev, address: u8, byte: u8) -> Result {
-    let tx_buf = [write_cmd(), address, byte];
+fn read_byte<SPI>(spi: &mut SPI, hw_addr: u8, address: u8) -> Result<u8>
+where
+    SPI: SpiDevice,
+    SPI::Error: std::error::Error + Send + Sync + 'static,
+{
+    let tx_buf = [read_cmd(hw_addr), address, 0];
+    let mut rx_buf = [0u8; 3];
+    spi.transfer(&mut rx_buf, &tx_buf)?;
+    Ok(rx_buf[2])
+}
+
+fn write_byte<SPI>(spi: &mut SPI, hw_addr: u8, address: u8, byte: u8) -> Result
+where
+    SPI: SpiDevice,
+    SPI::Error: std::error::Error + Send + Sync + 'static,
+{
+    let tx_buf = [write_cmd(hw_addr), address, byte];
     spi.write(&tx_buf)?;
     Ok(())
 }
 
-fn write_cmd() -> u8 {
-    0x40 | (HARDWARE_ADDRESS << 1) | 0
+fn write_cmd(hw_addr: u8) -> u8 {
+    0x40 | (hw_addr << 1) | 0
+}
+
+fn read_cmd(hw_addr: u8) -> u8 {
+    0x40 | (hw_addr << 1) | 1
 }
 
-fn read_cmd() -> u8 {
-    0x40 | (HARDWARE_ADDRESS << 1) | 1
+// A thin `spidev`-backed `SpiDevice` so existing Raspberry Pi users can keep
+// driving the expander over `/dev/spidevN.N` without reaching for a full HAL.
+#[cfg(feature = "spidev")]
+mod spidev_device {
+    use embedded_hal::spi::{
+        Error, ErrorKind, ErrorType, Mode, Operation, Phase, Polarity, SpiDevice, MODE_0,
+    };
+    use spidev::{Spidev, SpidevOptions, SpidevTransfer, SPI_MODE_0, SPI_MODE_1, SPI_MODE_2, SPI_MODE_3};
+    use std::io::Write;
+
+    /// Bus parameters for the `spidev` backend. The defaults reproduce the
+    /// historical behaviour: 100 kHz and SPI mode 0.
+    #[derive(Clone, Copy)]
+    pub struct SpiConfig {
+        pub frequency_hz: u32,
+        pub mode: Mode,
+    }
+
+    impl Default for SpiConfig {
+        fn default() -> Self {
+            SpiConfig {
+                frequency_hz: 100_000,
+                mode: MODE_0,
+            }
+        }
+    }
+
+    pub struct SpidevDevice {
+        spi: Spidev,
+    }
+
+    impl SpidevDevice {
+        pub fn open(device: &str) -> std::io::Result<Self> {
+            SpidevDevice::open_with_config(device, SpiConfig::default())
+        }
+
+        pub fn open_with_config(device: &str, config: SpiConfig) -> std::io::Result<Self> {
+            let mut spi = Spidev::open(device)?;
+            spi.configure(
+                SpidevOptions::new()
+                    .bits_per_word(8)
+                    .max_speed_hz(config.frequency_hz)
+                    .mode(mode_flags(config.mode)),
+            )?;
+            Ok(SpidevDevice { spi })
+        }
+    }
+
+    fn mode_flags(mode: embedded_hal::spi::Mode) -> spidev::SpiModeFlags {
+        match (mode.polarity, mode.phase) {
+            (Polarity::IdleLow, Phase::CaptureOnFirstTransition) => SPI_MODE_0,
+            (Polarity::IdleLow, Phase::CaptureOnSecondTransition) => SPI_MODE_1,
+            (Polarity::IdleHigh, Phase::CaptureOnFirstTransition) => SPI_MODE_2,
+            (Polarity::IdleHigh, Phase::CaptureOnSecondTransition) => SPI_MODE_3,
+        }
+    }
+
+    #[derive(Debug)]
+    pub struct SpidevError(std::io::Error);
+
+    impl std::fmt::Display for SpidevError {
+        fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            self.0.fmt(f)
+        }
+    }
+
+    impl std::error::Error for SpidevError {}
+
+    impl Error for SpidevError {
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::Other
+        }
+    }
+
+    impl ErrorType for SpidevDevice {
+        type Error = SpidevError;
+    }
+
+    impl SpiDevice for SpidevDevice {
+        fn transaction(&mut self, operations: &mut [Operation<u8>]) -> Result<(), SpidevError> {
+            for op in operations {
+                match op {
+                    Operation::Read(buf) => {
+                        let tx = vec![0u8; buf.len()];
+                        let mut transfer = SpidevTransfer::read_write(&tx, buf);
+                        self.spi.transfer(&mut transfer).map_err(SpidevError)?;
+                    }
+                    Operation::Write(buf) => {
+                        self.spi.write_all(buf).map_err(SpidevError)?;
+                    }
+                    Operation::Transfer(read, write) => {
+                        let mut transfer = SpidevTransfer::read_write(write, read);
+                        self.spi.transfer(&mut transfer).map_err(SpidevError)?;
+                    }
+                    Operation::TransferInPlace(buf) => {
+                        let tx = buf.to_vec();
+                        let mut transfer = SpidevTransfer::read_write(&tx, buf);
+                        self.spi.transfer(&mut transfer).map_err(SpidevError)?;
+                    }
+                    Operation::DelayNs(_) => {}
+                }
+            }
+            Ok(())
+        }
+    }
 }